@@ -5,13 +5,35 @@ use uuid::Uuid;
 use sglang_router_rs::tokenizer::traits::Tokenizer;
 use sglang_router_rs::tokenizer::stream::DecodeStream;
 use sglang_router_rs::tool_parser::ToolParser;
+use sglang_router_rs::reasoning_parser::{ReasoningParser, ReasoningParserFactory};
 use sglang_router_rs::protocols::common::{Tool, ToolChoice, ToolChoiceValue, Usage, StringOrArray};
 use sglang_router_rs::tokenizer::stop::StopSequenceDecoder;
 use sglang_router_rs::grpc_client::sglang_proto as proto;
-use sglang_router_rs::protocols::chat::{ChatCompletionStreamResponse, ChatMessageDelta, ChatStreamChoice};
+use sglang_router_rs::protocols::chat::{ChatCompletionStreamResponse, ChatMessageDelta, ChatStreamChoice, ToolCallDelta, FunctionCallDelta, ChatLogProbs, ChatLogProbToken, ChatTopLogProb};
 use sglang_router_rs::grpc_client::sglang_proto::generate_response::Response::{Chunk, Complete, Error};
 use sglang_router_rs::routers::grpc::utils::create_stop_decoder;
 
+/// Per-index partial tool-call state accumulated across SSE frames.
+///
+/// The parser reports a `tool_index` for each function it is assembling; we
+/// stream the name in the first delta and the argument fragments thereafter,
+/// only validating the accumulated arguments once the call boundary is reached.
+#[derive(Default)]
+struct ToolCallPartial {
+    function_index: Option<i32>, // tool_index currently being accumulated
+    function_arguments: String,  // growing raw argument JSON
+    call_count: usize,           // number of calls finalized for this choice
+}
+
+/// A decoded token fragment paired with the logprob data of the position that
+/// produced it. Collected during the decode/stop pass so that logprobs stay
+/// aligned to the exact characters emitted in a delta.
+struct TokenPiece {
+    text: String,
+    logprob: f32,
+    top: Vec<(u32, f32)>, // (token_id, logprob) for the top-k alternatives
+}
+
 pub struct ResponseConverter {
     pub tokenizer: Arc<dyn Tokenizer>,
     pub tool_parser: Option<Box<dyn ToolParser>>,
@@ -26,11 +48,17 @@ pub struct ResponseConverter {
     pub stream_buffers: HashMap<u32, String>, // Per-index text buffers
     pub decode_streams: HashMap<u32, DecodeStream>, // Per-index incremental decoders
     pub has_tool_calls: HashMap<u32, bool>, // Track if tool calls were emitted
+    tool_call_partials: HashMap<u32, ToolCallPartial>, // Per-index in-flight tool call
     pub is_first_chunk: HashMap<u32, bool>, // Track first chunk per index
     pub prompt_tokens: HashMap<u32, i32>, // Track prompt tokens per index (from chunks)
     pub completion_tokens: HashMap<u32, i32>, // Track completion tokens per index (cumulative)
     pub initial_prompt_tokens: Option<i32>, // Initial prompt tokens from request (if available)
     pub skip_special_tokens: bool, // Whether to skip special tokens when decoding
+    pub logprobs: bool, // Whether the request asked for token logprobs
+    pub top_logprobs: Option<u32>, // Number of top alternatives to report per token
+    pub enable_reasoning: bool, // Route <think> spans to reasoning_content (chat only)
+    reasoning_parser_factory: ReasoningParserFactory, // Builds per-model reasoning parsers
+    reasoning_parsers: HashMap<u32, Option<Box<dyn ReasoningParser>>>, // Per-index reasoning state
 }
 
 impl ResponseConverter {
@@ -43,6 +71,8 @@ impl ResponseConverter {
         stop: Option<StringOrArray>,
         stop_token_ids: Option<Vec<u32>>,
         skip_special_tokens: bool,
+        logprobs: bool,
+        top_logprobs: Option<u32>,
     ) -> Self {
         // Create stop decoder if needed
         let stop_decoder = if stop.is_some() || stop_token_ids.is_some() {
@@ -84,11 +114,17 @@ impl ResponseConverter {
             stream_buffers: HashMap::new(),
             decode_streams: HashMap::new(),
             has_tool_calls: HashMap::new(),
+            tool_call_partials: HashMap::new(),
             is_first_chunk: HashMap::new(),
             prompt_tokens: HashMap::new(),
             completion_tokens: HashMap::new(),
             initial_prompt_tokens: None,
             skip_special_tokens,
+            logprobs,
+            top_logprobs,
+            enable_reasoning: true,
+            reasoning_parser_factory: ReasoningParserFactory::default(),
+            reasoning_parsers: HashMap::new(),
         }
     }
 
@@ -116,20 +152,33 @@ impl ResponseConverter {
                 }
                 self.completion_tokens.insert(index, chunk.completion_tokens);
 
-                // Process tokens through stop decoder or incremental decoder
+                // Process tokens through stop decoder or incremental decoder. Each
+                // fragment the decoder actually emits is recorded as a `TokenPiece`
+                // carrying the logprob of the position that produced it, so logprobs
+                // stay aligned to the characters sent (held and stop-trimmed tokens
+                // contribute no piece).
+                let record_logprobs = self.logprobs;
+                let mut pieces: Vec<TokenPiece> = Vec::new();
                 let chunk_text = if let Some(ref mut stop_decoder) = self.stop_decoder {
                     let mut text = String::new();
-                    for &token_id in &chunk.token_ids {
-                        match stop_decoder.process_token(token_id).unwrap_or(
+                    for (pos, &token_id) in chunk.token_ids.iter().enumerate() {
+                        let output = stop_decoder.process_token(token_id).unwrap_or(
                             sglang_router_rs::tokenizer::SequenceDecoderOutput::Held
-                        ) {
-                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Text(t) => text.push_str(&t),
-                            sglang_router_rs::tokenizer::SequenceDecoderOutput::StoppedWithText(t) => {
-                                text.push_str(&t);
-                                break;
+                        );
+                        let (fragment, stop) = match output {
+                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Text(t) => (Some(t), false),
+                            sglang_router_rs::tokenizer::SequenceDecoderOutput::StoppedWithText(t) => (Some(t), true),
+                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Stopped => (None, true),
+                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Held => (None, false),
+                        };
+                        if let Some(fragment) = fragment {
+                            if record_logprobs && !fragment.is_empty() {
+                                pieces.push(token_piece(&chunk, pos, fragment.clone()));
                             }
-                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Stopped => break,
-                            sglang_router_rs::tokenizer::SequenceDecoderOutput::Held => {}
+                            text.push_str(&fragment);
+                        }
+                        if stop {
+                            break;
                         }
                     }
                     text
@@ -137,14 +186,17 @@ impl ResponseConverter {
                     let decode_stream = self.decode_streams.entry(index).or_insert_with(|| {
                         DecodeStream::new(
                             self.tokenizer.clone(),
-                            &[], 
+                            &[],
                             self.skip_special_tokens,
                         )
                     });
 
                     let mut text_parts = Vec::new();
-                    for &token_id in &chunk.token_ids {
+                    for (pos, &token_id) in chunk.token_ids.iter().enumerate() {
                         if let Ok(Some(text)) = decode_stream.step(token_id) {
+                            if record_logprobs && !text.is_empty() {
+                                pieces.push(token_piece(&chunk, pos, text.clone()));
+                            }
                             text_parts.push(text);
                         }
                     }
@@ -155,8 +207,53 @@ impl ResponseConverter {
                      return Ok(None);
                 }
 
-                // Send first chunk with role
-                if is_first {
+                // Separate explicit chain-of-thought from user-visible content. The
+                // parser holds back a short suffix when a delimiter may be split
+                // across chunk boundaries, so `reasoning_text`/`normal_text` only
+                // ever contain fully-decided fragments.
+                let (reasoning_text, normal_text) = self.split_reasoning(index, &chunk_text)?;
+                let reasoning_content = if reasoning_text.is_empty() { None } else { Some(reasoning_text.clone()) };
+
+                // The opening delta carries the assistant `role`, and that is the
+                // only field special-cased for the first chunk. Reasoning routing
+                // and tool-call parsing run on the first fragment exactly as on
+                // later ones, so a tool call that begins in chunk 0 is fed to the
+                // stateful parser instead of leaking out as plain `content` and
+                // corrupting the parser's view of subsequent fragments.
+                let role = if is_first { Some("assistant".to_string()) } else { None };
+
+                if normal_text.is_empty() && reasoning_text.is_empty() {
+                    // Nothing decided this fragment. The first chunk still emits the
+                    // opening role delta so clients see the assistant turn begin.
+                    if is_first {
+                        return Ok(Some(ChatCompletionStreamResponse {
+                            id: self.request_id.clone(),
+                            object: "chat.completion.chunk".to_string(),
+                            created: self.created,
+                            model: self.model.clone(),
+                            system_fingerprint: self.system_fingerprint.clone(),
+                            choices: vec![ChatStreamChoice {
+                                index,
+                                delta: ChatMessageDelta {
+                                    role,
+                                    content: None,
+                                    tool_calls: None,
+                                    reasoning_content: None,
+                                },
+                                logprobs: None,
+                                finish_reason: None,
+                                matched_stop: None,
+                            }],
+                            usage: None,
+                        }));
+                    }
+                    return Ok(None);
+                }
+
+                // Reasoning-only fragment: route it to `reasoning_content` and wait
+                // for the closing delimiter before anything reaches `content`.
+                if normal_text.is_empty() {
+                    let logprobs = self.logprobs_for_emitted(&pieces, &chunk_text, &reasoning_text);
                     return Ok(Some(ChatCompletionStreamResponse {
                         id: self.request_id.clone(),
                         object: "chat.completion.chunk".to_string(),
@@ -166,39 +263,93 @@ impl ResponseConverter {
                         choices: vec![ChatStreamChoice {
                             index,
                             delta: ChatMessageDelta {
-                                role: Some("assistant".to_string()),
-                                content: if chunk_text.is_empty() { None } else { Some(chunk_text.clone()) },
+                                role,
+                                content: None,
                                 tool_calls: None,
-                                reasoning_content: None,
+                                reasoning_content,
                             },
-                            logprobs: None,
+                            logprobs,
                             finish_reason: None,
                             matched_stop: None,
                         }],
                         usage: None,
                     }));
                 }
-                
-                if chunk_text.is_empty() {
-                    return Ok(None);
-                }
 
-                // Update stream buffer
-                let stream_buffer = self.stream_buffers.entry(index).or_default();
-                stream_buffer.push_str(&chunk_text);
+                // Handle tool calls: feed the user-visible fragment into the parser,
+                // which returns both the text it classifies as *outside* a call and
+                // any function-call deltas at call boundaries. The outside-call prose
+                // is streamed as ordinary content per chunk rather than buffered to
+                // the end, and a populated `tool_calls` vector rides the same delta.
+                let tool_choice_enabled = self.tools.is_some()
+                    && self.tool_parser.is_some()
+                    && !matches!(self.tool_choice, Some(ToolChoice::Value(ToolChoiceValue::None)));
 
-                // Handle tool calls
-                if let (Some(ref _tools), Some(ref mut _tool_parser)) = (self.tools.as_ref(), self.tool_parser.as_mut()) {
-                    let tool_choice_enabled = !matches!(
-                        self.tool_choice,
-                        Some(ToolChoice::Value(ToolChoiceValue::None))
-                    );
+                if tool_choice_enabled {
+                    let (tool_normal, tool_calls) = self.parse_tool_call_fragment(index, &normal_text)?;
+                    let has_calls = !tool_calls.is_empty();
+                    if has_calls {
+                        self.has_tool_calls.insert(index, true);
+                    }
+                    if !tool_normal.is_empty() {
+                        self.stream_buffers.entry(index).or_default().push_str(&tool_normal);
+                    }
 
-                    if tool_choice_enabled {
-                        // TODO: Implement tool parsing
+                    let content = if tool_normal.is_empty() { None } else { Some(tool_normal.clone()) };
+                    if content.is_none() && !has_calls && reasoning_content.is_none() {
+                        // Fragment fully consumed inside an in-flight call. The first
+                        // chunk still emits its opening role delta.
+                        if is_first {
+                            return Ok(Some(ChatCompletionStreamResponse {
+                                id: self.request_id.clone(),
+                                object: "chat.completion.chunk".to_string(),
+                                created: self.created,
+                                model: self.model.clone(),
+                                system_fingerprint: self.system_fingerprint.clone(),
+                                choices: vec![ChatStreamChoice {
+                                    index,
+                                    delta: ChatMessageDelta {
+                                        role,
+                                        content: None,
+                                        tool_calls: None,
+                                        reasoning_content: None,
+                                    },
+                                    logprobs: None,
+                                    finish_reason: None,
+                                    matched_stop: None,
+                                }],
+                                usage: None,
+                            }));
+                        }
+                        return Ok(None);
                     }
+                    let logprobs = self.logprobs_for_emitted(&pieces, &chunk_text, &tool_normal);
+                    return Ok(Some(ChatCompletionStreamResponse {
+                        id: self.request_id.clone(),
+                        object: "chat.completion.chunk".to_string(),
+                        created: self.created,
+                        model: self.model.clone(),
+                        system_fingerprint: self.system_fingerprint.clone(),
+                        choices: vec![ChatStreamChoice {
+                            index,
+                            delta: ChatMessageDelta {
+                                role,
+                                content,
+                                tool_calls: if has_calls { Some(tool_calls) } else { None },
+                                reasoning_content,
+                            },
+                            logprobs,
+                            finish_reason: None,
+                            matched_stop: None,
+                        }],
+                        usage: None,
+                    }));
                 }
 
+                // Plain content: buffer for the `Complete` parity path and stream now.
+                self.stream_buffers.entry(index).or_default().push_str(&normal_text);
+                let logprobs = self.logprobs_for_emitted(&pieces, &chunk_text, &normal_text);
+
                 Ok(Some(ChatCompletionStreamResponse {
                     id: self.request_id.clone(),
                     object: "chat.completion.chunk".to_string(),
@@ -208,12 +359,12 @@ impl ResponseConverter {
                     choices: vec![ChatStreamChoice {
                         index,
                         delta: ChatMessageDelta {
-                            role: None,
-                            content: Some(chunk_text),
+                            role,
+                            content: Some(normal_text),
                             tool_calls: None,
-                            reasoning_content: None,
+                            reasoning_content,
                         },
-                        logprobs: None,
+                        logprobs,
                         finish_reason: None,
                         matched_stop: None,
                     }],
@@ -223,25 +374,75 @@ impl ResponseConverter {
             Some(Complete(complete)) => {
                 let index = complete.index;
                 
-                // Flush decoder
-                let mut final_text = self.stream_buffers.remove(&index).unwrap_or_default();
+                // Content already streamed per chunk lives in the buffer; anything
+                // produced here is the *unemitted* tail. Keep them apart so the
+                // terminal delta never re-sends content the client already saw.
+                let streamed = self.stream_buffers.remove(&index).unwrap_or_default();
+                let mut tail = String::new();
                 if let Some(ref mut decode_stream) = self.decode_streams.get_mut(&index) {
                     if let Ok(Some(remaining)) = decode_stream.flush() {
-                        final_text.push_str(&remaining);
+                        tail.push_str(&remaining);
                     }
                 }
                 self.decode_streams.remove(&index);
 
-                // If final_text is empty, it might be a non-streaming request where we need to decode output_ids
-                if final_text.is_empty() && !complete.output_ids.is_empty() {
-                     match self.tokenizer.decode(&complete.output_ids, self.skip_special_tokens) {
-                        Ok(text) => final_text = text,
+                // A non-streaming request arrives as a single `Complete` with no
+                // preceding chunks, so nothing has run through the incremental
+                // decode/reasoning path yet. Detect that (no buffered content, no
+                // per-index reasoning state) and decode the whole sequence here;
+                // this is the only path that attaches logprobs to the terminal delta.
+                let from_output_ids = streamed.is_empty()
+                    && tail.is_empty()
+                    && !self.reasoning_parsers.contains_key(&index)
+                    && !complete.output_ids.is_empty();
+                if from_output_ids {
+                    match self.tokenizer.decode(&complete.output_ids, self.skip_special_tokens) {
+                        Ok(text) => tail = text,
                         Err(_) => {} // Ignore decoding error
                     }
                 }
 
-                // Determine finish reason
-                let finish_reason = if complete.finish_reason.is_empty() {
+                // Separate chain-of-thought from the freshly decoded text: the
+                // non-streaming path never passed through the per-chunk reasoning
+                // split (a no-op when the request disabled reasoning).
+                let mut reasoning_acc = String::new();
+                if from_output_ids && !tail.is_empty() {
+                    let (reasoning_text, normal_text) = self.split_reasoning(index, &tail)?;
+                    tail = normal_text;
+                    reasoning_acc.push_str(&reasoning_text);
+                }
+
+                // Flush anything the streaming reasoning parser held back awaiting a
+                // delimiter. The held content tail was never emitted, so it joins the
+                // terminal delta alongside the held reasoning text.
+                if let Some(Some(mut parser)) = self.reasoning_parsers.remove(&index) {
+                    let flushed = parser.flush()?;
+                    if !flushed.normal_text.is_empty() {
+                        tail.push_str(&flushed.normal_text);
+                    }
+                    if !flushed.reasoning_text.is_empty() {
+                        reasoning_acc.push_str(&flushed.reasoning_text);
+                    }
+                }
+                let reasoning_content = if reasoning_acc.is_empty() {
+                    None
+                } else {
+                    Some(reasoning_acc)
+                };
+
+                // Finalize any in-flight tool call and validate its arguments.
+                if let Some(partial) = self.tool_call_partials.remove(&index) {
+                    if partial.function_index.is_some() {
+                        validate_tool_arguments(&partial.function_arguments)?;
+                    }
+                }
+                let emitted_tool_calls = self.has_tool_calls.get(&index).copied().unwrap_or(false);
+
+                // Determine finish reason. A completion that emitted tool calls ends
+                // with `tool_calls` regardless of what the scheduler reported.
+                let finish_reason = if emitted_tool_calls {
+                    "tool_calls".to_string()
+                } else if complete.finish_reason.is_empty() {
                     "stop".to_string()
                 } else {
                     complete.finish_reason.clone()
@@ -268,6 +469,31 @@ impl ResponseConverter {
                     completion_tokens_details: None,
                 });
 
+                // The terminal delta carries only the unemitted tail. Logprobs are
+                // attached exclusively on the non-streaming decode path: in the
+                // streaming case every fragment's logprobs were already emitted per
+                // chunk, so re-sending them here would make a concatenating client
+                // double-count. The `output_ids` sequence is decoded through a fresh
+                // `DecodeStream` so multi-byte tokens resolve to their real bytes.
+                let content = if emitted_tool_calls || tail.is_empty() {
+                    None
+                } else {
+                    Some(tail)
+                };
+                let logprobs = if from_output_ids {
+                    match content.as_deref() {
+                        Some(text) => self.logprobs_from_tokens(
+                            &complete.output_ids,
+                            &complete.logprobs,
+                            &top_logprobs_alternatives(&complete.top_logprobs),
+                            text,
+                        ),
+                        None => None,
+                    }
+                } else {
+                    None
+                };
+
                 Ok(Some(ChatCompletionStreamResponse {
                     id: self.request_id.clone(),
                     object: "chat.completion.chunk".to_string(),
@@ -278,11 +504,11 @@ impl ResponseConverter {
                         index,
                         delta: ChatMessageDelta {
                             role: None,
-                            content: if final_text.is_empty() { None } else { Some(final_text) },
+                            content,
                             tool_calls: None,
-                            reasoning_content: None,
+                            reasoning_content,
                         },
-                        logprobs: None,
+                        logprobs,
                         finish_reason: Some(finish_reason),
                         matched_stop: None, // Simplified
                     }],
@@ -295,6 +521,274 @@ impl ResponseConverter {
             None => Ok(None),
         }
     }
+
+    /// Build the OpenAI-shaped `logprobs` object for a slice of emitted text.
+    ///
+    /// `pieces` are the per-token fragments produced by the same decode/stop pass
+    /// that built `full` (the whole decoded chunk), so held and stop-trimmed
+    /// tokens never appear. `emitted` is the substring of `full` actually sent in
+    /// the current delta (content or reasoning); only the pieces overlapping its
+    /// byte range are reported, keeping the array aligned to the characters sent.
+    fn logprobs_for_emitted(
+        &self,
+        pieces: &[TokenPiece],
+        full: &str,
+        emitted: &str,
+    ) -> Option<ChatLogProbs> {
+        if !self.logprobs || emitted.is_empty() || pieces.is_empty() {
+            return None;
+        }
+
+        let start = full.find(emitted).unwrap_or(0);
+        let end = start + emitted.len();
+
+        let mut offset = 0usize;
+        let mut content = Vec::new();
+        for piece in pieces {
+            let piece_start = offset;
+            offset += piece.text.len();
+            // Keep pieces whose byte span intersects the emitted window.
+            if offset <= start || piece_start >= end {
+                continue;
+            }
+            content.push(self.piece_to_logprob(piece));
+        }
+
+        if content.is_empty() {
+            None
+        } else {
+            Some(ChatLogProbs { content })
+        }
+    }
+
+    /// Build logprobs for a terminal delta by decoding `token_ids` through a
+    /// fresh `DecodeStream` (so multi-byte tokens resolve correctly) and aligning
+    /// the resulting pieces to `emitted`.
+    fn logprobs_from_tokens(
+        &self,
+        token_ids: &[u32],
+        values: &[f32],
+        tops: &[Vec<(u32, f32)>],
+        emitted: &str,
+    ) -> Option<ChatLogProbs> {
+        if !self.logprobs || token_ids.is_empty() {
+            return None;
+        }
+
+        let mut stream = DecodeStream::new(self.tokenizer.clone(), &[], self.skip_special_tokens);
+        let mut pieces: Vec<TokenPiece> = Vec::new();
+        let mut full = String::new();
+        for (pos, &token_id) in token_ids.iter().enumerate() {
+            if let Ok(Some(text)) = stream.step(token_id) {
+                if !text.is_empty() {
+                    full.push_str(&text);
+                    pieces.push(TokenPiece {
+                        text,
+                        logprob: values.get(pos).copied().unwrap_or(0.0),
+                        top: tops.get(pos).cloned().unwrap_or_default(),
+                    });
+                }
+            }
+        }
+        if let Ok(Some(remaining)) = stream.flush() {
+            if !remaining.is_empty() {
+                full.push_str(&remaining);
+                pieces.push(TokenPiece { text: remaining, logprob: 0.0, top: Vec::new() });
+            }
+        }
+
+        self.logprobs_for_emitted(&pieces, &full, emitted)
+    }
+
+    /// Convert a single decoded token piece into an OpenAI logprob entry,
+    /// decoding any top-k alternatives the scheduler reported for that position.
+    fn piece_to_logprob(&self, piece: &TokenPiece) -> ChatLogProbToken {
+        let top_logprobs = match self.top_logprobs {
+            Some(k) if k > 0 => piece
+                .top
+                .iter()
+                .take(k as usize)
+                .map(|&(tid, lp)| {
+                    let tok = self
+                        .tokenizer
+                        .decode(&[tid], self.skip_special_tokens)
+                        .unwrap_or_default();
+                    ChatTopLogProb {
+                        bytes: tok.as_bytes().to_vec(),
+                        token: tok,
+                        logprob: lp,
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        ChatLogProbToken {
+            bytes: piece.text.as_bytes().to_vec(),
+            token: piece.text.clone(),
+            logprob: piece.logprob,
+            top_logprobs,
+        }
+    }
+
+    /// Split a decoded fragment into `(reasoning_text, normal_text)` using a
+    /// per-index reasoning parser built for the current model.
+    ///
+    /// The parser owns the state machine over the reasoning span (e.g. the
+    /// `<think>...</think>` delimiters) and holds back a short suffix when a
+    /// delimiter may straddle a chunk boundary, so both returned fragments are
+    /// always fully decided. Models without a reasoning parser pass everything
+    /// through as normal content.
+    fn split_reasoning(&mut self, index: u32, text: &str) -> anyhow::Result<(String, String)> {
+        // The raw-prompt completion surface opts out: reasoning delimiters are
+        // part of the verbatim output and must stay in `content`.
+        if !self.enable_reasoning {
+            return Ok((String::new(), text.to_string()));
+        }
+
+        let model = self.model.clone();
+        let factory = &self.reasoning_parser_factory;
+        let parser = self
+            .reasoning_parsers
+            .entry(index)
+            .or_insert_with(|| factory.create(&model));
+
+        match parser {
+            Some(parser) => {
+                let result = parser.parse_incremental(text)?;
+                Ok((result.reasoning_text, result.normal_text))
+            }
+            None => Ok((String::new(), text.to_string())),
+        }
+    }
+
+    /// Feed a decoded text fragment into the tool parser and translate the
+    /// resulting call events into streaming `ToolCallDelta`s for this choice.
+    ///
+    /// The parser reports a `tool_index` per function it is assembling. When the
+    /// incoming index differs from the one currently being accumulated a new call
+    /// has started, so the previous call's arguments are finalized and validated
+    /// before its successor's first delta is emitted. The function name is sent
+    /// only in the opening delta; subsequent deltas carry argument fragments.
+    ///
+    /// Returns the parser's outside-call `normal_text` (assistant prose that is
+    /// not part of any tool call) alongside the emitted deltas so the caller can
+    /// stream it as ordinary content.
+    fn parse_tool_call_fragment(
+        &mut self,
+        index: u32,
+        chunk_text: &str,
+    ) -> anyhow::Result<(String, Vec<ToolCallDelta>)> {
+        let tools = self.tools.as_deref().unwrap_or(&[]);
+        let parser = self
+            .tool_parser
+            .as_mut()
+            .expect("tool parser checked by caller");
+        let result = parser.parse_incremental(chunk_text, tools)?;
+        let normal_text = result.normal_text.clone();
+
+        let mut deltas = Vec::new();
+        let partial = self.tool_call_partials.entry(index).or_default();
+
+        for call in result.calls {
+            let tool_index = call.tool_index as i32;
+            let is_new_function = match partial.function_index {
+                Some(current) => current != tool_index,
+                None => true,
+            };
+
+            if is_new_function {
+                // Finalize the previous call before starting the next one.
+                if partial.function_index.is_some() {
+                    validate_tool_arguments(&partial.function_arguments)?;
+                    partial.call_count += 1;
+                }
+
+                let name = call.name.clone().unwrap_or_default();
+                let id = generate_tool_call_id(
+                    &self.model,
+                    &name,
+                    partial.call_count,
+                    self.history_tool_calls_count,
+                );
+
+                partial.function_index = Some(tool_index);
+                partial.function_arguments.clear();
+                partial.function_arguments.push_str(&call.parameters);
+
+                deltas.push(ToolCallDelta {
+                    index: partial.call_count as u32,
+                    id: Some(id),
+                    r#type: Some("function".to_string()),
+                    function: Some(FunctionCallDelta {
+                        name: Some(name),
+                        arguments: if call.parameters.is_empty() {
+                            None
+                        } else {
+                            Some(call.parameters)
+                        },
+                    }),
+                });
+            } else if !call.parameters.is_empty() {
+                // Continuation of the current call: stream argument fragments only.
+                partial.function_arguments.push_str(&call.parameters);
+                deltas.push(ToolCallDelta {
+                    index: partial.call_count as u32,
+                    id: None,
+                    r#type: None,
+                    function: Some(FunctionCallDelta {
+                        name: None,
+                        arguments: Some(call.parameters),
+                    }),
+                });
+            }
+        }
+
+        Ok((normal_text, deltas))
+    }
+}
+
+// Logprob extraction relies on these proto fields, which must line up with the
+// emitted tokens positionally:
+//   * `GenerateStreamChunk.logprobs: Vec<f32>` / `GenerateComplete.logprobs`
+//   * `GenerateStreamChunk.top_logprobs: Vec<TopLogProbs>` / `..Complete..`
+//   * `TopLogProbs { token_ids: Vec<u32>, logprobs: Vec<f32> }`
+// Every access below is positional and length-guarded (`get`, `zip`), so a
+// missing or short array degrades to "no logprob for that token" rather than
+// panicking or misaligning the reported array.
+
+/// Record a decoded fragment together with the logprob reported for its token.
+fn token_piece(chunk: &proto::GenerateStreamChunk, pos: usize, text: String) -> TokenPiece {
+    TokenPiece {
+        text,
+        logprob: chunk.logprobs.get(pos).copied().unwrap_or(0.0),
+        top: chunk.top_logprobs.get(pos).map(top_alternatives).unwrap_or_default(),
+    }
+}
+
+/// Flatten a single proto top-logprob entry into `(token_id, logprob)` pairs.
+fn top_alternatives(top: &proto::TopLogProbs) -> Vec<(u32, f32)> {
+    top.token_ids
+        .iter()
+        .copied()
+        .zip(top.logprobs.iter().copied())
+        .collect()
+}
+
+/// Flatten the per-position proto top-logprob entries of a terminal message.
+fn top_logprobs_alternatives(tops: &[proto::TopLogProbs]) -> Vec<Vec<(u32, f32)>> {
+    tops.iter().map(top_alternatives).collect()
+}
+
+/// Ensure the accumulated function arguments parse as valid JSON before the
+/// call is considered complete. An empty argument string is accepted.
+fn validate_tool_arguments(arguments: &str) -> anyhow::Result<()> {
+    if arguments.is_empty() {
+        return Ok(());
+    }
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("tool call arguments must be valid JSON"))
 }
 
 pub fn generate_tool_call_id(
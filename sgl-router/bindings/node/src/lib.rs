@@ -3,24 +3,116 @@
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode, ErrorStrategy};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use tokio::sync::Notify;
 use tokio_stream::StreamExt;
 use sglang_router_rs::tokenizer::create_tokenizer_from_file;
 use sglang_router_rs::tokenizer::traits::Tokenizer;
 use sglang_router_rs::grpc_client::sglang_scheduler::SglangSchedulerClient;
 use sglang_router_rs::protocols::chat::ChatCompletionRequest;
+use sglang_router_rs::protocols::common::Usage;
 use sglang_router_rs::routers::grpc::utils::{process_chat_messages, generate_tool_constraints};
 use uuid::Uuid;
 
 mod converter;
 use converter::ResponseConverter;
 
+/// Accumulated state for a single choice index in a non-streaming completion.
+#[derive(Default)]
+struct ChoiceAccumulator {
+    content: String,
+    reasoning_content: String,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+}
+
+/// Raw-prompt (`/v1/completions`-style) request. Unlike the chat surface this
+/// skips chat templating entirely: the prompt(s) are tokenized verbatim. A
+/// `prompt` may be a single string or an array of strings for batched
+/// completion, in which case one generate request is issued per prompt and the
+/// responses are interleaved back into `choices[]` keyed by prompt index.
+#[derive(Debug, serde::Deserialize)]
+struct CompletionRequest {
+    model: String,
+    prompt: serde_json::Value,
+    #[serde(default)]
+    max_tokens: Option<i32>,
+    #[serde(default)]
+    stop: Option<serde_json::Value>,
+    #[serde(default)]
+    n: Option<i32>,
+    #[serde(default)]
+    seed: Option<i64>,
+    #[serde(default)]
+    skip_special_tokens: Option<bool>,
+}
+
+impl CompletionRequest {
+    /// Normalize the `prompt` field into the list of prompts to generate for.
+    fn prompts(&self) -> Result<Vec<String>> {
+        match &self.prompt {
+            serde_json::Value::String(s) => Ok(vec![s.clone()]),
+            serde_json::Value::Array(items) => items
+                .iter()
+                .map(|item| {
+                    item.as_str().map(|s| s.to_string()).ok_or_else(|| {
+                        Error::new(Status::InvalidArg, "prompt array must contain strings")
+                    })
+                })
+                .collect(),
+            _ => Err(Error::new(
+                Status::InvalidArg,
+                "prompt must be a string or an array of strings",
+            )),
+        }
+    }
+
+    /// Build the chat-shaped request the gRPC builder consumes. No messages are
+    /// supplied (templating is skipped); only the sampling parameters carry over.
+    fn to_generate_request(&self) -> Result<ChatCompletionRequest> {
+        let request = serde_json::json!({
+            "model": self.model,
+            "messages": [],
+            "max_tokens": self.max_tokens,
+            "stop": self.stop,
+            "n": self.n.unwrap_or(1),
+            "seed": self.seed,
+            "skip_special_tokens": self.skip_special_tokens.unwrap_or(true),
+        });
+        serde_json::from_value(request)
+            .map_err(|e| Error::new(Status::InvalidArg, format!("Invalid completion request: {}", e)))
+    }
+}
+
 #[napi]
 pub struct SglangClient {
     client: Arc<SglangSchedulerClient>,
     tokenizer: Arc<dyn Tokenizer>,
 }
 
+/// Handle returned by [`SglangClient::chat_completion_stream`] that lets the JS
+/// caller abort an in-flight completion. Dropping or timing out on the JS side
+/// should call [`StreamHandle::cancel`] so the backend stops generating.
+#[napi]
+pub struct StreamHandle {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+#[napi]
+impl StreamHandle {
+    /// Signal the spawned stream task to stop. The callback fires once more with
+    /// a terminal "Stream cancelled by caller" error and the stream is dropped
+    /// promptly.
+    #[napi]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
 #[napi]
 impl SglangClient {
     #[napi(factory)]
@@ -98,28 +190,40 @@ impl SglangClient {
             chat_request.stop.clone(),
             chat_request.stop_token_ids.clone(),
             chat_request.skip_special_tokens,
+            chat_request.logprobs.unwrap_or(false),
+            chat_request.top_logprobs,
         );
         converter.initial_prompt_tokens = Some(prompt_tokens);
 
-        let mut full_content = String::new();
-        let mut final_usage = None;
-        let mut finish_reason = None;
+        // Aggregate converted chunks per choice index. Each sampled sequence
+        // carries its own `index` in the proto chunks, so a request with `n > 1`
+        // produces several interleaved streams that we collapse into one
+        // `choices[]` entry each.
+        let mut accumulators: HashMap<u32, ChoiceAccumulator> = HashMap::new();
 
         while let Some(result) = stream.next().await {
              match result {
                  Ok(proto_response) => {
                      match converter.convert_chunk(proto_response) {
                          Ok(Some(openai_chunk)) => {
-                             if let Some(choice) = openai_chunk.choices.first() {
+                             for choice in &openai_chunk.choices {
+                                 let acc = accumulators.entry(choice.index).or_default();
                                  if let Some(content) = &choice.delta.content {
-                                     full_content.push_str(content);
+                                     acc.content.push_str(content);
+                                 }
+                                 if let Some(reasoning) = &choice.delta.reasoning_content {
+                                     acc.reasoning_content.push_str(reasoning);
                                  }
                                  if choice.finish_reason.is_some() {
-                                     finish_reason = choice.finish_reason.clone();
+                                     acc.finish_reason = choice.finish_reason.clone();
                                  }
                              }
-                             if openai_chunk.usage.is_some() {
-                                 final_usage = openai_chunk.usage;
+                             // Usage is reported per-index on the completion chunk.
+                             if let (Some(usage), Some(choice)) =
+                                 (&openai_chunk.usage, openai_chunk.choices.first())
+                             {
+                                 let acc = accumulators.entry(choice.index).or_default();
+                                 acc.usage = Some(usage.clone());
                              }
                          },
                          Ok(None) => {},
@@ -130,20 +234,50 @@ impl SglangClient {
              }
         }
 
+        // Emit choices in index order and sum per-index usage into the aggregate.
+        let mut indices: Vec<u32> = accumulators.keys().copied().collect();
+        indices.sort_unstable();
+
+        let mut prompt_tokens = 0u32;
+        let mut completion_tokens = 0u32;
+        let choices: Vec<serde_json::Value> = indices
+            .iter()
+            .map(|index| {
+                let acc = &accumulators[index];
+                if let Some(usage) = &acc.usage {
+                    // Prompt tokens are shared across sequences; count them once.
+                    prompt_tokens = prompt_tokens.max(usage.prompt_tokens);
+                    completion_tokens += usage.completion_tokens;
+                }
+                let mut message = serde_json::json!({
+                    "role": "assistant",
+                    "content": acc.content
+                });
+                // Surface separated chain-of-thought only when the model produced it.
+                if !acc.reasoning_content.is_empty() {
+                    message["reasoning_content"] = serde_json::json!(acc.reasoning_content);
+                }
+                serde_json::json!({
+                    "index": index,
+                    "message": message,
+                    "finish_reason": acc.finish_reason.clone().unwrap_or_else(|| "stop".to_string())
+                })
+            })
+            .collect();
+
+        let usage = serde_json::json!({
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        });
+
         let response = serde_json::json!({
             "id": converter.request_id,
             "object": "chat.completion",
             "created": converter.created,
             "model": converter.model,
-            "choices": [{
-                "index": 0,
-                "message": {
-                    "role": "assistant",
-                    "content": full_content
-                },
-                "finish_reason": finish_reason.unwrap_or("stop".to_string())
-            }],
-            "usage": final_usage
+            "choices": choices,
+            "usage": usage
         });
 
         Ok(response.to_string())
@@ -152,12 +286,16 @@ impl SglangClient {
     /// Streaming chat completion
     /// Accepts a callback function that receives JSON chunks
     #[napi(ts_args_type = "requestJson: string, callback: (err: null | Error, chunk: string) => void")]
-    pub fn chat_completion_stream(&self, request_json: String, callback: ThreadsafeFunction<String, ErrorStrategy::CalleeHandled>) -> Result<()> {
+    pub fn chat_completion_stream(&self, request_json: String, callback: ThreadsafeFunction<String, ErrorStrategy::CalleeHandled>) -> Result<StreamHandle> {
          let chat_request: ChatCompletionRequest = serde_json::from_str(&request_json)
             .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to parse request JSON: {}", e)))?;
-        
+
         let client = self.client.clone();
         let tokenizer = self.tokenizer.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let notify = Arc::new(Notify::new());
+        let task_cancelled = cancelled.clone();
+        let task_notify = notify.clone();
 
         // We spawn a tokio task to handle the stream asynchronously
         // NAPI's ThreadsafeFunction allows us to call back into JS from this thread
@@ -202,33 +340,277 @@ impl SglangClient {
                     chat_request.stop.clone(),
                     chat_request.stop_token_ids.clone(),
                     chat_request.skip_special_tokens,
+                    chat_request.logprobs.unwrap_or(false),
+                    chat_request.top_logprobs,
                 );
                 converter.initial_prompt_tokens = Some(prompt_tokens);
 
-                while let Some(result) = stream.next().await {
-                    match result {
-                        Ok(proto_response) => {
-                             match converter.convert_chunk(proto_response) {
-                                 Ok(Some(openai_chunk)) => {
-                                     let json_str = serde_json::to_string(&openai_chunk)
-                                         .map_err(|e| anyhow::anyhow!("JSON serialization error: {}", e))?;
-                                     callback.call(Ok(json_str), ThreadsafeFunctionCallMode::Blocking);
-                                 },
-                                 Ok(None) => {},
-                                 Err(e) => return Err(anyhow::anyhow!("Conversion error: {}", e)),
-                             }
+                loop {
+                    tokio::select! {
+                        biased;
+                        // Cancellation wins so the backend stops promptly: drop the
+                        // stream and surface a terminal error to the callback.
+                        _ = task_notify.notified() => {
+                            return Err(anyhow::anyhow!("Stream cancelled by caller"));
+                        }
+                        next = stream.next() => {
+                            let Some(result) = next else { break };
+                            match result {
+                                Ok(proto_response) => {
+                                     match converter.convert_chunk(proto_response) {
+                                         Ok(Some(openai_chunk)) => {
+                                             let json_str = serde_json::to_string(&openai_chunk)
+                                                 .map_err(|e| anyhow::anyhow!("JSON serialization error: {}", e))?;
+                                             callback.call(Ok(json_str), ThreadsafeFunctionCallMode::Blocking);
+                                         },
+                                         Ok(None) => {},
+                                         Err(e) => return Err(anyhow::anyhow!("Conversion error: {}", e)),
+                                     }
+                                }
+                                Err(e) => return Err(anyhow::anyhow!("Stream error: {}", e)),
+                            }
                         }
-                        Err(e) => return Err(anyhow::anyhow!("Stream error: {}", e)),
+                    }
+                    // Also honor a cancellation observed between frames.
+                    if task_cancelled.load(Ordering::SeqCst) {
+                        return Err(anyhow::anyhow!("Stream cancelled by caller"));
                     }
                 }
                 Ok(())
             }.await;
 
+            // `converter` is dropped here, releasing its decoders and parsers.
             if let Err(e) = process_result {
                 callback.call(Err(Error::new(Status::GenericFailure, e.to_string())), ThreadsafeFunctionCallMode::Blocking);
             }
         });
 
+        Ok(StreamHandle { cancelled, notify })
+    }
+
+    /// Non-streaming text completion.
+    ///
+    /// Accepts a raw-prompt request (`prompt` string or array of strings) and
+    /// returns an OpenAI `text_completion` object. Each prompt is tokenized
+    /// directly, issued as its own generate request, and the results are
+    /// interleaved into `choices[]` keyed by prompt index.
+    #[napi]
+    pub async fn completion(&self, request_json: String) -> Result<String> {
+        let request: CompletionRequest = serde_json::from_str(&request_json)
+            .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to parse request JSON: {}", e)))?;
+
+        let prompts = request.prompts()?;
+        let generate_request = request.to_generate_request()?;
+        let skip_special_tokens = request.skip_special_tokens.unwrap_or(true);
+        let request_id = format!("cmpl-{}", Uuid::new_v4());
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut choices: Vec<serde_json::Value> = Vec::new();
+        let mut prompt_tokens_total = 0u32;
+        let mut completion_tokens_total = 0u32;
+        let mut next_index = 0u32;
+
+        for prompt in &prompts {
+            let token_ids = self.tokenizer.encode(prompt)
+                .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to tokenize: {}", e)))?
+                .token_ids()
+                .to_vec();
+            let prompt_tokens = token_ids.len() as i32;
+            prompt_tokens_total += prompt_tokens.max(0) as u32;
+
+            let proto_request = self.client.build_generate_request_from_chat(
+                request_id.clone(),
+                &generate_request,
+                prompt.clone(),
+                token_ids,
+                None,
+                None,
+            ).map_err(|e| Error::new(Status::GenericFailure, format!("Failed to build generate request: {}", e)))?;
+
+            let mut stream = self.client.generate(proto_request).await
+                .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to send request: {}", e)))?;
+
+            let mut converter = ResponseConverter::new(
+                self.tokenizer.clone(),
+                generate_request.model.clone(),
+                request_id.clone(),
+                None,
+                None,
+                generate_request.stop.clone(),
+                generate_request.stop_token_ids.clone(),
+                skip_special_tokens,
+                false,
+                None,
+            );
+            converter.initial_prompt_tokens = Some(prompt_tokens);
+            // Raw completions return model output verbatim; never strip reasoning.
+            converter.enable_reasoning = false;
+
+            let mut accumulators: HashMap<u32, ChoiceAccumulator> = HashMap::new();
+            while let Some(result) = stream.next().await {
+                let proto_response = result
+                    .map_err(|e| Error::new(Status::GenericFailure, format!("Stream error: {}", e)))?;
+                match converter.convert_chunk(proto_response) {
+                    Ok(Some(openai_chunk)) => {
+                        for choice in &openai_chunk.choices {
+                            let acc = accumulators.entry(choice.index).or_default();
+                            if let Some(content) = &choice.delta.content {
+                                acc.content.push_str(content);
+                            }
+                            if choice.finish_reason.is_some() {
+                                acc.finish_reason = choice.finish_reason.clone();
+                            }
+                        }
+                        if let (Some(usage), Some(choice)) =
+                            (&openai_chunk.usage, openai_chunk.choices.first())
+                        {
+                            accumulators.entry(choice.index).or_default().usage = Some(usage.clone());
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Err(Error::new(Status::GenericFailure, format!("Conversion error: {}", e))),
+                }
+            }
+
+            let mut seq_indices: Vec<u32> = accumulators.keys().copied().collect();
+            seq_indices.sort_unstable();
+            for seq in seq_indices {
+                let acc = &accumulators[&seq];
+                if let Some(usage) = &acc.usage {
+                    completion_tokens_total += usage.completion_tokens;
+                }
+                choices.push(serde_json::json!({
+                    "index": next_index,
+                    "text": acc.content,
+                    "logprobs": serde_json::Value::Null,
+                    "finish_reason": acc.finish_reason.clone().unwrap_or_else(|| "stop".to_string())
+                }));
+                next_index += 1;
+            }
+        }
+
+        let response = serde_json::json!({
+            "id": request_id,
+            "object": "text_completion",
+            "created": created,
+            "model": request.model,
+            "choices": choices,
+            "usage": {
+                "prompt_tokens": prompt_tokens_total,
+                "completion_tokens": completion_tokens_total,
+                "total_tokens": prompt_tokens_total + completion_tokens_total
+            }
+        });
+
+        Ok(response.to_string())
+    }
+
+    /// Streaming text completion.
+    ///
+    /// Mirrors [`SglangClient::completion`] but emits `text_completion.chunk`
+    /// objects via the callback. Batched prompts stream concurrently; each chunk
+    /// is tagged with the choice index `prompt_index * n + sequence_index` so the
+    /// caller can reassemble them.
+    #[napi(ts_args_type = "requestJson: string, callback: (err: null | Error, chunk: string) => void")]
+    pub fn completion_stream(&self, request_json: String, callback: ThreadsafeFunction<String, ErrorStrategy::CalleeHandled>) -> Result<()> {
+        let request: CompletionRequest = serde_json::from_str(&request_json)
+            .map_err(|e| Error::new(Status::InvalidArg, format!("Failed to parse request JSON: {}", e)))?;
+
+        let prompts = request.prompts()?;
+        let generate_request = request.to_generate_request()?;
+        let skip_special_tokens = request.skip_special_tokens.unwrap_or(true);
+        let n_per_prompt = request.n.unwrap_or(1).max(1) as u32;
+        let request_id = format!("cmpl-{}", Uuid::new_v4());
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // One task per prompt so the streams interleave; each task tags its
+        // chunks with a choice index derived from the prompt's position.
+        for (prompt_index, prompt) in prompts.into_iter().enumerate() {
+            let client = self.client.clone();
+            let tokenizer = self.tokenizer.clone();
+            let generate_request = generate_request.clone();
+            let request_id = request_id.clone();
+            let model = request.model.clone();
+            let callback = callback.clone();
+            let index_base = prompt_index as u32 * n_per_prompt;
+
+            tokio::spawn(async move {
+                let process_result = async {
+                    let token_ids = tokenizer.encode(&prompt)
+                        .map_err(|e| anyhow::anyhow!("Failed to tokenize: {}", e))?
+                        .token_ids()
+                        .to_vec();
+                    let prompt_tokens = token_ids.len() as i32;
+
+                    let proto_request = client.build_generate_request_from_chat(
+                        request_id.clone(),
+                        &generate_request,
+                        prompt.clone(),
+                        token_ids,
+                        None,
+                        None,
+                    ).map_err(|e| anyhow::anyhow!("Failed to build generate request: {}", e))?;
+
+                    let mut stream = client.generate(proto_request).await
+                        .map_err(|e| anyhow::anyhow!("Failed to send request: {}", e))?;
+
+                    let mut converter = ResponseConverter::new(
+                        tokenizer.clone(),
+                        generate_request.model.clone(),
+                        request_id.clone(),
+                        None,
+                        None,
+                        generate_request.stop.clone(),
+                        generate_request.stop_token_ids.clone(),
+                        skip_special_tokens,
+                        false,
+                        None,
+                    );
+                    converter.initial_prompt_tokens = Some(prompt_tokens);
+                    // Raw completions return model output verbatim; never strip reasoning.
+                    converter.enable_reasoning = false;
+
+                    while let Some(result) = stream.next().await {
+                        let proto_response = result
+                            .map_err(|e| anyhow::anyhow!("Stream error: {}", e))?;
+                        match converter.convert_chunk(proto_response) {
+                            Ok(Some(openai_chunk)) => {
+                                for choice in &openai_chunk.choices {
+                                    let text = choice.delta.content.clone().unwrap_or_default();
+                                    let chunk = serde_json::json!({
+                                        "id": request_id,
+                                        "object": "text_completion.chunk",
+                                        "created": created,
+                                        "model": model,
+                                        "choices": [{
+                                            "index": index_base + choice.index,
+                                            "text": text,
+                                            "logprobs": serde_json::Value::Null,
+                                            "finish_reason": choice.finish_reason
+                                        }]
+                                    });
+                                    callback.call(Ok(chunk.to_string()), ThreadsafeFunctionCallMode::Blocking);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => return Err(anyhow::anyhow!("Conversion error: {}", e)),
+                        }
+                    }
+                    Ok(())
+                }.await;
+
+                if let Err(e) = process_result {
+                    callback.call(Err(Error::new(Status::GenericFailure, e.to_string())), ThreadsafeFunctionCallMode::Blocking);
+                }
+            });
+        }
+
         Ok(())
     }
 }